@@ -1,30 +1,32 @@
-use std::ops::{Add, Sub};
+use std::ops::Add;
 use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 #[cfg(test)]
 mod tests {
+    use std::ops::Sub;
     use std::panic;
+    use std::thread;
 
     use super::*;
 
     #[test]
     fn take_available_test() {
-        let mut b = TokenBucket::new(1000, 100, Duration::from_secs(1));
-        sleep(Duration::from_secs(1));
+        let mut b = TokenBucket::with_clock(1000, 100, Duration::from_secs(1), MockClock::new());
+        b.clock.advance(Duration::from_secs(1));
         assert_eq!(100, b.take_available(100));
-        sleep(Duration::from_secs(1));
+        b.clock.advance(Duration::from_secs(1));
         assert_eq!(100, b.take_available(100));
     }
 
     #[test]
     fn try_take_test() {
-        let mut b = TokenBucket::new(1000, 100, Duration::from_secs(1));
+        let mut b = TokenBucket::with_clock(1000, 100, Duration::from_secs(1), MockClock::new());
         assert!(!b.try_take(100, Duration::ZERO));
-        sleep(Duration::from_secs(1));
+        b.clock.advance(Duration::from_secs(1));
         assert!(b.try_take(100, Duration::ZERO));
-        sleep(Duration::from_secs(1));
+        b.clock.advance(Duration::from_secs(1));
         assert!(b.try_take(100, Duration::ZERO));
 
         assert!(b.try_take(100, Duration::from_secs(1)));
@@ -32,40 +34,37 @@ mod tests {
 
     #[test]
     fn take_test() {
-        let mut b = TokenBucket::new(1000, 100, Duration::from_secs(1));
-        let begin = Instant::now();
+        let mut b = TokenBucket::with_clock(1000, 100, Duration::from_secs(1), MockClock::new());
+        let begin = b.clock.now();
+        // take 对欠缺的部分通过 MockClock 做虚拟等待，不阻塞测试线程；断言虚拟时钟恰好前进了 2 秒
         b.take(200);
-        assert_eq!(Instant::now().sub(begin).as_secs(), 2)
+        assert_eq!(2, b.clock.now().sub(begin).as_secs());
     }
 
     #[test]
     fn available_test() {
-        let mut b = TokenBucket::new(1000, 100, Duration::from_secs(1));
-        sleep(Duration::from_secs(1));
+        let mut b = TokenBucket::with_clock(1000, 100, Duration::from_secs(1), MockClock::new());
+        b.clock.advance(Duration::from_secs(1));
         assert_eq!(100, b.available());
     }
 
-    #[test]
-    fn time_test() {
-        let b = TokenBucket::new(100000, 1, Duration::from_secs(1));
-        assert_eq!(1, b.current_tick(Instant::now().add(Duration::from_secs(1))));
-    }
-
     #[test]
     fn adjust_test() {
         let mut b = TokenBucket::new(100000, 1, Duration::from_secs(1));
-        assert_eq!(0, b.current_tick(Instant::now()));
-        assert_eq!(1, b.current_tick(Instant::now().add(Duration::from_secs(1))));
-        assert_eq!(100, b.current_tick(Instant::now().add(Duration::from_secs(100))));
+        assert_eq!(0, b.available_fractions);
+
+        let t1 = b.last_update.add(Duration::from_secs(1));
+        b.adjust_available_fractions(t1);
+        assert_eq!(MULTIPLIER as i64, b.available_fractions);
 
-        b.adjust_available_tokens(100);
-        assert_eq!(100, b.available_tokens);
-        b.adjust_available_tokens(200);
-        assert_eq!(100, b.available_tokens);
-        b.adjust_available_tokens(400);
-        assert_eq!(200, b.available_tokens);
-        b.adjust_available_tokens(10000);
-        assert_eq!(9600, b.available_tokens);
+        let t100 = t1.add(Duration::from_secs(99));
+        b.adjust_available_fractions(t100);
+        assert_eq!(100 * MULTIPLIER as i64, b.available_fractions);
+
+        // 已经追上容量后，再往后推进时间也不会继续增长
+        let t_overflow = t100.add(Duration::from_secs(1_000_000));
+        b.adjust_available_fractions(t_overflow);
+        assert_eq!(100000 * MULTIPLIER as i64, b.available_fractions);
     }
 
     #[test]
@@ -82,6 +81,154 @@ mod tests {
         f(1, 0, 1, "quantum".to_string());
         f(1, 1, 0, "duration".to_string());
     }
+
+    #[test]
+    fn sub_second_fill_interval_test() {
+        // 200ms 填充一次，相当于每秒 5 个令牌；过去这里会因为 fill_interval < 1s 而 panic
+        let mut b = TokenBucket::with_clock(100, 1, Duration::from_millis(200), MockClock::new());
+        assert!(b.try_take(1, Duration::from_millis(210)));
+    }
+
+    #[test]
+    fn rate_limiter_consume_test() {
+        let ops = TokenBucket::new(10, 10, Duration::from_secs(1));
+        let bytes = TokenBucket::new(100, 100, Duration::from_secs(1));
+        let mut limiter = RateLimiter::new(Some(ops), Some(bytes));
+
+        // 初始两个桶都是空的，任何消耗都应该失败
+        assert!(!limiter.consume(1, 1));
+
+        sleep(Duration::from_secs(1));
+        assert!(limiter.consume(5, 50));
+
+        // bytes 桶余量不足时，ops 桶不应该被扣减
+        assert!(!limiter.consume(5, 1000));
+        assert!(limiter.consume(5, 50));
+    }
+
+    #[test]
+    fn rate_limiter_manual_replenish_test() {
+        let ops = TokenBucket::new(10, 10, Duration::from_secs(1));
+        let mut limiter = RateLimiter::new(Some(ops), None);
+
+        sleep(Duration::from_secs(1));
+        assert!(limiter.consume(10, 0));
+        assert!(!limiter.consume(1, 0));
+
+        limiter.manual_replenish(1, TokenType::Ops);
+        assert!(limiter.consume(1, 0));
+    }
+
+    #[test]
+    fn multi_window_from_header_test() {
+        let limiter = MultiWindowLimiter::from_header("20:1,100:120");
+        assert_eq!(2, limiter.buckets.len());
+    }
+
+    #[test]
+    fn multi_window_from_header_ignores_malformed_window_test() {
+        // 连续逗号留下的空窗口、非数字、count/seconds 为 0、多出字段的窗口都应该被忽略，而不是 panic
+        let limiter = MultiWindowLimiter::from_header("20:1,,100:120,abc:1,5:0,,1:2:3");
+        assert_eq!(2, limiter.buckets.len());
+    }
+
+    #[test]
+    fn multi_window_try_take_test() {
+        let mut limiter = MultiWindowLimiter::from_header("5:1,10:2");
+        assert!(!limiter.try_take(1, Duration::ZERO));
+
+        sleep(Duration::from_secs(2));
+        assert!(limiter.try_take(5, Duration::ZERO));
+
+        // 1 秒窗口已经用尽，即便 2 秒窗口还有余量，也要等待已用尽的 1 秒窗口恢复
+        assert!(!limiter.try_take(1, Duration::ZERO));
+    }
+
+    #[test]
+    fn reserve_immediate_test() {
+        let mut b = TokenBucket::new(1000, 100, Duration::from_secs(1));
+        // 直接注入令牌而不用真的等待，验证余量充足时 reserve 立即生效
+        b.manual_replenish(50);
+        let r = b.reserve(50);
+        assert_eq!(Duration::ZERO, b.delay(&r));
+    }
+
+    #[test]
+    fn reserve_deficit_test() {
+        let mut b = TokenBucket::new(1000, 100, Duration::from_secs(1));
+        let r = b.reserve(200);
+        // 余量允许变成负数，调用方可以查询还要等待多久，而不是被阻塞在这里
+        assert!(b.delay(&r) > Duration::ZERO);
+        assert!(b.delay(&r) <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn reserve_delay_reads_injected_clock_test() {
+        // ready_at 是用 MockClock 产生的，delay() 也必须经过同一个 clock 读取当前时间，
+        // 否则在这里会被真实的 Instant::now() 污染，结果要么不确定要么彻底错误。
+        let mut b = TokenBucket::with_clock(1000, 100, Duration::from_secs(1), MockClock::new());
+        let r = b.reserve(200);
+        let expected = Duration::from_secs(2);
+        assert_eq!(expected, b.delay(&r));
+
+        b.clock.advance(Duration::from_secs(1));
+        assert_eq!(Duration::from_secs(1), b.delay(&r));
+    }
+
+    #[test]
+    fn cancel_before_ready_test() {
+        let mut b = TokenBucket::new(1000, 100, Duration::from_secs(1));
+        let r = b.reserve(200);
+        assert_eq!(0, b.take_available(1));
+
+        b.cancel(r);
+        // 预订还没到期就被取消，令牌应该物归原主，桶回到预订之前的状态
+        assert_eq!(0, b.take_available(1));
+    }
+
+    #[test]
+    fn multi_window_retry_after_test() {
+        let mut limiter = MultiWindowLimiter::from_header("5:1,10:2");
+        assert!(limiter.retry_after() > Duration::ZERO);
+
+        sleep(Duration::from_secs(2));
+        assert_eq!(Duration::ZERO, limiter.retry_after());
+    }
+
+    #[test]
+    fn shared_bucket_reserve_delay_test() {
+        let bucket = SharedBucket::new(1000, 100, Duration::from_secs(1));
+        let r = bucket.reserve(200);
+        assert!(bucket.delay(&r) > Duration::ZERO);
+    }
+
+    #[test]
+    fn shared_bucket_clone_test() {
+        let bucket = SharedBucket::new(1000, 100, Duration::from_secs(1));
+        let other = bucket.clone();
+
+        sleep(Duration::from_secs(1));
+        // 克隆出来的句柄和原句柄共享同一份底层状态
+        assert_eq!(100, other.take_available(100));
+        assert_eq!(0, bucket.take_available(1));
+    }
+
+    #[test]
+    fn shared_bucket_across_threads_test() {
+        let bucket = SharedBucket::new(1000, 1000, Duration::from_secs(1));
+        sleep(Duration::from_secs(1));
+
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let bucket = bucket.clone();
+                thread::spawn(move || bucket.take_available(100))
+            })
+            .collect();
+
+        let total: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        // 10 个线程总共只应该瓜分到一次填充量的令牌，不会因为并发而多算或者少算
+        assert_eq!(1000, total);
+    }
 }
 
 pub trait RateLimit {
@@ -97,133 +244,449 @@ pub trait RateLimit {
 
 const INFINITY_DURATION: Duration = Duration::MAX;
 
+// 令牌以 1/MULTIPLIER 为最小计量单位累积，从而避免低速率、亚秒级填充间隔下的整数截断
+const MULTIPLIER: u64 = 256;
+
+// Clock 把"当前时间"和"阻塞等待"都抽象出来，使 TokenBucket 不必直接依赖 Instant::now()
+// 和 std::thread::sleep，测试里可以换成 MockClock 来确定性地验证补充/等待时间的计算。
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+// SystemClock 是生产环境下使用的默认时钟，直接对接系统时间与真实阻塞等待
+#[derive(Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        sleep(duration)
+    }
+}
+
+// MockClock 只在显式调用 advance 或经由 sleep 做虚拟等待时才前进，不会阻塞线程，让补充/等待时间的断言在微秒级完成
+pub struct MockClock {
+    base: Instant,
+    offset: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            base: Instant::now(),
+            offset: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut offset = self.offset.lock().unwrap();
+        *offset += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base + *self.offset.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
 
-struct TokenBucket {
+pub struct TokenBucket<C: Clock = SystemClock> {
     capacity: u64,
     fill_interval: Duration,
     // 单次填充令牌数
     quantum: u64,
-    available_tokens: i64,
-    create_time: Instant,
-    // 最新的间隔次数，当需要等待的时候计算截止时间
-    last_tick: u64,
+    // 以 1/MULTIPLIER 个令牌为单位的可用数量，支撑分数令牌的精确计量
+    available_fractions: i64,
+    // 上一次把流逝时间折算进 available_fractions 的时间点
+    last_update: Instant,
 
-    mu: Arc<Mutex<i64>>,
+    clock: C,
 }
 
 
-impl TokenBucket {
-    pub fn new(capacity: u64, quantum: u64, fill_interval: Duration) -> TokenBucket {
-        if capacity <= 0 {
+impl TokenBucket<SystemClock> {
+    pub fn new(capacity: u64, quantum: u64, fill_interval: Duration) -> TokenBucket<SystemClock> {
+        TokenBucket::with_clock(capacity, quantum, fill_interval, SystemClock)
+    }
+}
+
+impl<C: Clock> TokenBucket<C> {
+    pub fn with_clock(capacity: u64, quantum: u64, fill_interval: Duration, clock: C) -> TokenBucket<C> {
+        if capacity == 0 {
             panic!("capacity is not > 0")
         }
-        if quantum <= 0 {
+        if quantum == 0 {
             panic!("quantum is not > 0")
         }
-        if fill_interval < Duration::from_secs(1) {
-            panic!("fill interval is not >= 1 sec")
+        if fill_interval <= Duration::ZERO {
+            panic!("fill interval is not > 0")
         }
         TokenBucket {
             capacity,
             fill_interval,
             quantum,
-            available_tokens: 0,
-            create_time: Instant::now(),
-            last_tick: 0,
-            mu: Arc::new(Mutex::new(0)),
-        }
-    }
-    fn current_tick(&self, now: Instant) -> u64 {
-        let sub = now - self.create_time;
-        let b = sub / self.fill_interval.as_secs() as u32;
-        return b.as_secs();
-    }
-    // adjustAvailableTokens 调整当前桶中应该有的令牌数量
-    fn adjust_available_tokens(&mut self, tick: u64) {
-        let last_tick = self.last_tick;
-        self.last_tick = tick;
-        if self.available_tokens as u64 >= self.capacity {
+            available_fractions: 0,
+            last_update: clock.now(),
+            clock,
+        }
+    }
+
+    fn capacity_fractions(&self) -> i64 {
+        (self.capacity * MULTIPLIER) as i64
+    }
+
+    // adjust_available_fractions 把 last_update 到 now 之间流逝的时间按填充速率折算成分数令牌
+    // 计入 available_fractions，并且只把"已经兑现"的那部分时间推进 last_update，
+    // 不足一个分数令牌的余量留到下一次调用，从而消除舍入漂移。
+    fn adjust_available_fractions(&mut self, now: Instant) {
+        if self.available_fractions >= self.capacity_fractions() {
+            self.last_update = now;
             return;
         }
-        self.available_tokens = ((tick - last_tick) * self.quantum) as i64;
-        if self.available_tokens >= self.capacity as i64 {
-            self.available_tokens = self.capacity as i64
+        let elapsed_ns = match now.checked_duration_since(self.last_update) {
+            Some(d) => d.as_nanos(),
+            None => return,
+        };
+        if elapsed_ns == 0 {
+            return;
         }
+        let numerator = self.quantum as u128 * MULTIPLIER as u128;
+        let denominator = self.fill_interval.as_nanos();
+        let gained = elapsed_ns * numerator / denominator;
+        if gained == 0 {
+            return;
+        }
+        let consumed_ns = gained * denominator / numerator;
+        self.available_fractions = ((self.available_fractions as i128 + gained as i128)
+            .min(self.capacity_fractions() as i128)) as i64;
+        self.last_update = self.last_update.add(Duration::from_nanos(consumed_ns as u64));
     }
+
     fn inner_take(&mut self, count: u64, now: Instant, max_wait: Duration) -> Result<Duration, ()> {
-        if count <= 0 {
+        if count == 0 {
             return Ok(Duration::from_secs(0));
         }
         if count > self.capacity {
             return Err(());
         }
-        let tick = self.current_tick(now);
-        self.adjust_available_tokens(tick);
-        let new_available = self.available_tokens - count as i64;
+        self.adjust_available_fractions(now);
+        let cost = (count * MULTIPLIER) as i64;
+        let new_available = self.available_fractions - cost;
         if new_available > 0 {
-            self.available_tokens = new_available;
+            self.available_fractions = new_available;
             return Ok(Duration::from_secs(0));
         }
-        let end_tick = (0 - new_available + self.quantum as i64 - 1) / self.quantum as i64;
-        let expected_end_time = self.create_time.add(self.fill_interval * end_tick as u32);
-        let wait_time = expected_end_time.sub(now);
+        let deficit = (0 - new_available) as u128;
+        let numerator = self.quantum as u128 * MULTIPLIER as u128;
+        let wait_ns = (deficit * self.fill_interval.as_nanos()).div_ceil(numerator);
+        let wait_time = Duration::from_nanos(wait_ns.min(u64::MAX as u128) as u64);
         if wait_time <= max_wait {
-            self.available_tokens = new_available;
+            self.available_fractions = new_available;
             return Ok(wait_time);
         }
         Err(())
     }
+
+    // manual_replenish 把 amount 个令牌还给桶，用于调用方中止或部分完成一次操作后的退款
+    pub fn manual_replenish(&mut self, amount: u64) {
+        let now = self.clock.now();
+        self.adjust_available_fractions(now);
+        let added = (amount * MULTIPLIER) as i64;
+        self.available_fractions = (self.available_fractions + added).min(self.capacity_fractions());
+    }
+
+    // wait_until_available 在不扣减令牌的前提下，返回攒够 count 个令牌还需要等待多久
+    fn wait_until_available(&mut self, count: u64, now: Instant) -> Duration {
+        self.adjust_available_fractions(now);
+        let cost = (count * MULTIPLIER) as i64;
+        let deficit = cost - self.available_fractions;
+        if deficit <= 0 {
+            return Duration::ZERO;
+        }
+        let numerator = self.quantum as u128 * MULTIPLIER as u128;
+        let wait_ns = (deficit as u128 * self.fill_interval.as_nanos()).div_ceil(numerator);
+        Duration::from_nanos(wait_ns.min(u64::MAX as u128) as u64)
+    }
+
+    // commit_take 无条件扣减 count 个令牌，余额可能因此变成负数；是否允许这次扣减由调用方自行判断
+    fn commit_take(&mut self, count: u64) {
+        self.available_fractions -= (count * MULTIPLIER) as i64;
+    }
+
+    // reserve 立即扣减 count 个令牌（允许余额变成负数），返回一个 Reservation 供调用方查询
+    // 还需要等待多久，自行接入定时器或事件循环，而不是像 take 那样阻塞当前线程。
+    pub fn reserve(&mut self, count: u64) -> Reservation {
+        let now = self.clock.now();
+        self.adjust_available_fractions(now);
+        let cost = (count * MULTIPLIER) as i64;
+        let new_available = self.available_fractions - cost;
+        self.available_fractions = new_available;
+
+        let ready_at = if new_available >= 0 {
+            now
+        } else {
+            let deficit = (0 - new_available) as u128;
+            let numerator = self.quantum as u128 * MULTIPLIER as u128;
+            let wait_ns = (deficit * self.fill_interval.as_nanos()).div_ceil(numerator);
+            now.add(Duration::from_nanos(wait_ns.min(u64::MAX as u128) as u64))
+        };
+        Reservation { ready_at, count }
+    }
+
+    // cancel 撤销一次预订；如果预订的等待时间还没有过去，就把扣减的令牌还给桶
+    pub fn cancel(&mut self, reservation: Reservation) {
+        if self.clock.now() < reservation.ready_at {
+            self.manual_replenish(reservation.count);
+        }
+    }
+
+    // delay 查询一次预订距离 ready_at 还需要等待多久。ready_at 是用 self.clock 产生的，
+    // 所以这里同样要经过 self.clock.now() 读取当前时间，而不是直接用 Instant::now()：
+    // 否则在注入 MockClock 的场景下，delay() 会读到与预订无关的真实时间，结果既不确定也不正确。
+    pub fn delay(&self, reservation: &Reservation) -> Duration {
+        reservation.ready_at.saturating_duration_since(self.clock.now())
+    }
+}
+
+// Reservation 代表一次已经立即生效的令牌扣减；ready_at 之前还需要等待多久通过产生它的
+// TokenBucket（或 SharedBucket）的 delay() 方法查询，这样才能经过同一个 Clock 读取时间。
+pub struct Reservation {
+    ready_at: Instant,
+    count: u64,
 }
 
 
-impl RateLimit for TokenBucket {
+impl<C: Clock> RateLimit for TokenBucket<C> {
     fn available(&mut self) -> u64 {
-        let mu = self.mu.clone();
-        let _lock = mu.lock();
-        self.adjust_available_tokens(self.current_tick(Instant::now()));
-        self.available_tokens as u64
+        let now = self.clock.now();
+        self.adjust_available_fractions(now);
+        (self.available_fractions.max(0) as u64) / MULTIPLIER
     }
 
     fn take(&mut self, count: u64) -> bool {
-        let mu = self.mu.clone();
-        let lock = mu.lock();
-        let res = self.inner_take(count, Instant::now(), INFINITY_DURATION);
+        let now = self.clock.now();
+        let res = self.inner_take(count, now, INFINITY_DURATION);
 
-        drop(lock);
         if let Ok(wait_time) = res {
-            sleep(wait_time);
+            self.clock.sleep(wait_time);
             return true;
         };
         false
     }
 
     fn take_available(&mut self, count: u64) -> u64 {
-        if count <= 0 {
+        if count == 0 {
             return 0;
         }
-        let mu = self.mu.clone();
-        let lock = mu.lock();
-
-        self.adjust_available_tokens(self.current_tick(Instant::now()));
-        if self.available_tokens >= count as i64 {
-            let real_count = self.available_tokens;
-            self.available_tokens = 0;
-            drop(lock);
-            return real_count as u64;
+        let now = self.clock.now();
+        self.adjust_available_fractions(now);
+        if self.available_fractions >= (count * MULTIPLIER) as i64 {
+            let real_count = (self.available_fractions.max(0) as u64) / MULTIPLIER;
+            self.available_fractions = 0;
+            return real_count;
         }
-        drop(lock);
         0
     }
 
     fn try_take(&mut self, count: u64, max_wait: Duration) -> bool {
-        let mu = self.mu.clone();
-        let lock = mu.lock();
-        let res = self.inner_take(count, Instant::now(), max_wait);
-        drop(lock);
+        let now = self.clock.now();
+        let res = self.inner_take(count, now, max_wait);
         if let Ok(wait_time) = res {
-            sleep(wait_time);
+            self.clock.sleep(wait_time);
             return true;
         }
         false
     }
 }
+
+// SharedBucket 把一个 TokenBucket 装进 Arc<Mutex<_>>，使其成为可以在多个线程间廉价克隆、
+// 共享同一份逻辑状态的句柄：所有方法都只需要 &self，调用方不必再自行包一层锁就能把
+// 同一个桶交给线程池里的多个 worker 使用。
+pub struct SharedBucket<C: Clock = SystemClock> {
+    inner: Arc<Mutex<TokenBucket<C>>>,
+}
+
+impl SharedBucket<SystemClock> {
+    pub fn new(capacity: u64, quantum: u64, fill_interval: Duration) -> SharedBucket<SystemClock> {
+        SharedBucket::with_clock(capacity, quantum, fill_interval, SystemClock)
+    }
+}
+
+impl<C: Clock> SharedBucket<C> {
+    pub fn with_clock(capacity: u64, quantum: u64, fill_interval: Duration, clock: C) -> SharedBucket<C> {
+        SharedBucket {
+            inner: Arc::new(Mutex::new(TokenBucket::with_clock(capacity, quantum, fill_interval, clock))),
+        }
+    }
+
+    pub fn available(&self) -> u64 {
+        self.inner.lock().unwrap().available()
+    }
+
+    pub fn take(&self, count: u64) -> bool {
+        self.inner.lock().unwrap().take(count)
+    }
+
+    pub fn take_available(&self, count: u64) -> u64 {
+        self.inner.lock().unwrap().take_available(count)
+    }
+
+    pub fn try_take(&self, count: u64, max_wait: Duration) -> bool {
+        self.inner.lock().unwrap().try_take(count, max_wait)
+    }
+
+    pub fn manual_replenish(&self, amount: u64) {
+        self.inner.lock().unwrap().manual_replenish(amount)
+    }
+
+    pub fn reserve(&self, count: u64) -> Reservation {
+        self.inner.lock().unwrap().reserve(count)
+    }
+
+    pub fn cancel(&self, reservation: Reservation) {
+        self.inner.lock().unwrap().cancel(reservation)
+    }
+
+    pub fn delay(&self, reservation: &Reservation) -> Duration {
+        self.inner.lock().unwrap().delay(reservation)
+    }
+}
+
+impl<C: Clock> Clone for SharedBucket<C> {
+    fn clone(&self) -> Self {
+        SharedBucket { inner: self.inner.clone() }
+    }
+}
+
+// TokenType 标记 RateLimiter 管理的两种互相独立的资源
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenType {
+    Ops,
+    Bytes,
+}
+
+// RateLimiter 同时限制操作次数和字节吞吐量两种资源，只有当两个桶都能满足各自的开销时
+// consume 才会成功，类似块设备 I/O 限流器同时约束 IOPS 与带宽的方式。
+pub struct RateLimiter {
+    ops_bucket: Option<TokenBucket>,
+    bytes_bucket: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(ops_bucket: Option<TokenBucket>, bytes_bucket: Option<TokenBucket>) -> RateLimiter {
+        RateLimiter { ops_bucket, bytes_bucket }
+    }
+
+    // consume 尝试立即扣减 ops 个操作令牌和 bytes 个字节令牌，两者都满足才会真正扣减，
+    // 否则不对任何一个桶产生影响。
+    pub fn consume(&mut self, ops: u64, bytes: u64) -> bool {
+        let ops_ok = match &mut self.ops_bucket {
+            Some(b) => b.try_take(ops, Duration::ZERO),
+            None => true,
+        };
+        if !ops_ok {
+            return false;
+        }
+        let bytes_ok = match &mut self.bytes_bucket {
+            Some(b) => b.try_take(bytes, Duration::ZERO),
+            None => true,
+        };
+        if !bytes_ok {
+            if let Some(b) = &mut self.ops_bucket {
+                b.manual_replenish(ops);
+            }
+            return false;
+        }
+        true
+    }
+
+    // manual_replenish 把令牌还给指定类型的桶，用于操作中止或部分完成（例如一次短读）时退款
+    pub fn manual_replenish(&mut self, amount: u64, token_type: TokenType) {
+        let bucket = match token_type {
+            TokenType::Ops => &mut self.ops_bucket,
+            TokenType::Bytes => &mut self.bytes_bucket,
+        };
+        if let Some(b) = bucket {
+            b.manual_replenish(amount);
+        }
+    }
+}
+
+// MultiWindowLimiter 同时维护多个时间窗口各自的 TokenBucket，例如 API 响应头里常见的
+// "20:1,100:120"（1 秒内 20 次 且 120 秒内 100 次），只有当所有窗口都允许时才放行请求。
+pub struct MultiWindowLimiter {
+    buckets: Vec<TokenBucket>,
+}
+
+impl MultiWindowLimiter {
+    pub fn new(buckets: Vec<TokenBucket>) -> MultiWindowLimiter {
+        MultiWindowLimiter { buckets }
+    }
+
+    // from_header 解析形如 "count:seconds" 的逗号分隔窗口列表，每个窗口对应一个
+    // capacity == quantum == count、fill_interval == seconds 的 TokenBucket。
+    // 这是服务端响应头的自我限流入口，不能信任其格式：缺字段、非数字或 count/seconds
+    // 为 0 的窗口会被直接忽略，而不是 panic 整个调用方。
+    pub fn from_header(header: &str) -> MultiWindowLimiter {
+        let buckets = header
+            .split(',')
+            .filter_map(|window| {
+                let mut parts = window.trim().splitn(2, ':');
+                let count: u64 = parts.next()?.trim().parse().ok()?;
+                let seconds: u64 = parts.next()?.trim().parse().ok()?;
+                if count == 0 || seconds == 0 {
+                    return None;
+                }
+                Some(TokenBucket::new(count, count, Duration::from_secs(seconds)))
+            })
+            .collect();
+        MultiWindowLimiter { buckets }
+    }
+
+    pub fn take(&mut self, count: u64) -> bool {
+        self.try_take(count, INFINITY_DURATION)
+    }
+
+    // try_take 只有当每一个窗口都能满足 count 个令牌的开销时才会成功，实际等待时间取所有窗口里最长的那个
+    pub fn try_take(&mut self, count: u64, max_wait: Duration) -> bool {
+        if self.buckets.iter().any(|b| count > b.capacity) {
+            return false;
+        }
+        let now = Instant::now();
+        let longest = self.buckets.iter_mut()
+            .map(|b| b.wait_until_available(count, now))
+            .max()
+            .unwrap_or(Duration::ZERO);
+        if longest > max_wait {
+            return false;
+        }
+        for b in self.buckets.iter_mut() {
+            b.commit_take(count);
+        }
+        sleep(longest);
+        true
+    }
+
+    // retry_after 返回所有窗口里，下一个令牌可用之前还需要等待的最长时间
+    pub fn retry_after(&mut self) -> Duration {
+        let now = Instant::now();
+        self.buckets.iter_mut()
+            .map(|b| b.wait_until_available(1, now))
+            .max()
+            .unwrap_or(Duration::ZERO)
+    }
+}